@@ -1,89 +1,137 @@
 use std::fs::File;
 use std::io::Write;
 use std::option::Option;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::string::String;
+use std::sync::Arc;
 
+use crate::diagnostics::{EnvVarCheck, ProbeOutcome, Report};
 use crate::env_reader::EnvReader;
+use crate::error::AppError;
 use crate::git::Git;
+use crate::hg::Hg;
 use crate::logger::Logger;
 use crate::svn::Svn;
 use crate::tfs::Tfs;
 use crate::utils::PeekOption;
+use crate::vcs::{find_vcs_root, VcsBackend};
+
+/// Marker files/directories that indicate the root of a Git, SVN, or Mercurial working
+/// copy, used to walk up the directory tree in search of a VCS root (see
+/// [`App::new`]/`--max-uplevel`).
+const VCS_ROOT_MARKERS: &[&str] = &[".git", ".svn", ".hg"];
+
+/// Environment variable names consulted by [`App::branch_from_environment`], in the
+/// order they're checked. Shared with [`App::diagnose`] so the diagnostic report lists
+/// exactly what was actually looked at.
+const ENVIRONMENT_BRANCH_VARIABLES: &[&str] = &[
+    // common names
+    "BRANCH",
+    "branch",
+    "GIT_BRANCH",
+    // TeamCity https://stackoverflow.com/questions/13278615/is-there-a-way-to-access-teamcity-system-properties-in-a-powershell-script
+    // https://www.jetbrains.com/help/teamcity/predefined-build-parameters.html#PredefinedBuildParameters-Branch-RelatedParameters
+    "build_branch",
+    "BUILD_BRANCH",
+    // Jenkins https://github.com/jenkinsci/pipeline-model-definition-plugin/pull/91
+    "BRANCH_NAME",
+    // Azure Devops/TFS https://docs.microsoft.com/en-us/azure/devops/pipelines/build/variables?view=azure-devops&tabs=yaml
+    "BUILD_SOURCEBRANCHNAME",
+    // Circle CI https://circleci.com/docs/2.0/env-vars/#built-in-environment-variables
+    "CIRCLE_BRANCH",
+    // Travis CI https://docs.travis-ci.com/user/environment-variables/#default-environment-variables
+    "TRAVIS_BRANCH",
+    // BitBucket pipelines https://confluence.atlassian.com/bitbucket/environment-variables-794502608.html
+    "BITBUCKET_BRANCH",
+    // GitLab pipelines https://docs.gitlab.com/ee/ci/variables/predefined_variables.html
+    "CI_MERGE_REQUEST_SOURCE_BRANCH_NAME",
+    "CI_COMMIT_REF_NAME",
+    // Appveyor https://www.appveyor.com/docs/environment-variables/
+    "APPVEYOR_PULL_REQUEST_HEAD_REPO_BRANCH",
+    "APPVEYOR_REPO_BRANCH",
+];
 
 pub struct App<'a> {
-    logger: &'a Logger,
+    logger: Arc<Logger>,
     env_reader: EnvReader<'a>,
     tfs_personal_access_token: Option<&'a str>,
+    /// The VCS root discovered by walking up from the current directory, bounded by
+    /// `max_uplevel`. `None` means no Git/SVN/Mercurial root was found within that bound,
+    /// so none of those backends are even attempted.
+    vcs_root: Option<PathBuf>,
 }
 
 impl<'a> App<'a> {
     pub fn new(
-        logger: &'a Logger,
+        logger: Arc<Logger>,
         env_reader: EnvReader<'a>,
         tfs_personal_access_token: Option<&'a str>,
+        max_uplevel: Option<u32>,
     ) -> App<'a> {
+        let vcs_root = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| find_vcs_root(&cwd, VCS_ROOT_MARKERS, max_uplevel));
+        let env_reader_logger = Arc::clone(&logger);
         return App {
             logger,
             env_reader: EnvReader::new(move |name| {
                 env_reader.env_variable(name).peek_or_default(
-                    |value| logger.log(&format!("${}={}", name, value)),
+                    |value| env_reader_logger.log(&format!("${}={}", name, value)),
                     "".to_string(),
                 )
             }),
             tfs_personal_access_token,
+            vcs_root,
         };
     }
 
-    fn branch_from_svn(&self) -> Option<String> {
+    fn branch_from_svn(&self) -> Result<Option<String>, AppError> {
         self.logger.log("Trying to guess branch name from SVN");
-        let svn = Svn::new(self.logger);
-        return svn
-            .branch()
-            .or(svn.branch_from_environment())
+        let svn = Svn::new(&self.logger, self.vcs_root.as_deref());
+        let branch = self
+            .first_detected_branch(vec![Box::new(Svn::new(&self.logger, self.vcs_root.as_deref()))])?
+            .or(svn.branch_from_environment());
+        return Ok(branch
             .if_some(|branch| self.logger.log(&format!("Found SVN branch {}", branch)))
-            .if_none(|| self.logger.log("Found no SVN branch"));
+            .if_none(|| self.logger.log("Found no SVN branch")));
+    }
+
+    /// Backends consulted, in order, once SVN and the generic build-runner environment
+    /// variables have both come up empty. Branch names derived this way are heuristic
+    /// (inferred from the checked out commit rather than read from authoritative VCS
+    /// metadata), so they're tried last. Adding support for another VCS here is a matter
+    /// of implementing [`VcsBackend`] for it and listing it below.
+    fn heuristic_branch_backends<'b>(&'b self) -> Vec<Box<dyn VcsBackend + 'b>> {
+        return vec![
+            Box::new(Git::new(&self.logger, self.vcs_root.as_deref())),
+            Box::new(Hg::new(&self.logger, self.vcs_root.as_deref())),
+        ];
     }
 
-    fn guess_branch_from_git(&self) -> Option<String> {
-        self.logger.log("Trying to guess branch name from Git");
-        let git = Git::new(self.logger);
-        return git.guess_branch();
+    /// Iterates the given backends in order and returns the branch reported by the first
+    /// one that detects itself as applicable to the current directory, logging which
+    /// backend claimed it. Backends beyond the first that detects are never consulted.
+    fn first_detected_branch<'b>(
+        &'b self,
+        backends: Vec<Box<dyn VcsBackend + 'b>>,
+    ) -> Result<Option<String>, AppError> {
+        for backend in backends {
+            if !backend.detect() {
+                continue;
+            }
+            self.logger
+                .log(&format!("{} claimed this directory", backend.name()));
+            return backend.branch();
+        }
+        return Ok(None);
     }
 
     fn branch_from_environment(&self) -> Option<String> {
         self.logger
             .log("Trying to guess branch name from environment variables");
-        // common names
-        return self
-            .env_reader
-            .env_variable("BRANCH")
-            .or(self.env_reader.env_variable("branch"))
-            .or(self.env_reader.env_variable("GIT_BRANCH"))
-            // TeamCity https://stackoverflow.com/questions/13278615/is-there-a-way-to-access-teamcity-system-properties-in-a-powershell-script
-            // https://www.jetbrains.com/help/teamcity/predefined-build-parameters.html#PredefinedBuildParameters-Branch-RelatedParameters
-            .or(self.env_reader.env_variable("build_branch"))
-            .or(self.env_reader.env_variable("BUILD_BRANCH"))
-            // Jenkins https://github.com/jenkinsci/pipeline-model-definition-plugin/pull/91
-            .or(self.env_reader.env_variable("BRANCH_NAME"))
-            // Azure Devops/TFS https://docs.microsoft.com/en-us/azure/devops/pipelines/build/variables?view=azure-devops&tabs=yaml
-            .or(self.env_reader.env_variable("BUILD_SOURCEBRANCHNAME"))
-            // Circle CI https://circleci.com/docs/2.0/env-vars/#built-in-environment-variables
-            .or(self.env_reader.env_variable("CIRCLE_BRANCH"))
-            // Travis CI https://docs.travis-ci.com/user/environment-variables/#default-environment-variables
-            .or(self.env_reader.env_variable("TRAVIS_BRANCH"))
-            // BitBucket pipelines https://confluence.atlassian.com/bitbucket/environment-variables-794502608.html
-            .or(self.env_reader.env_variable("BITBUCKET_BRANCH"))
-            // GitLab pipelines https://docs.gitlab.com/ee/ci/variables/predefined_variables.html
-            .or(self
-                .env_reader
-                .env_variable("CI_MERGE_REQUEST_SOURCE_BRANCH_NAME"))
-            .or(self.env_reader.env_variable("CI_COMMIT_REF_NAME"))
-            // Appveyor https://www.appveyor.com/docs/environment-variables/
-            .or(self
-                .env_reader
-                .env_variable("APPVEYOR_PULL_REQUEST_HEAD_REPO_BRANCH"))
-            .or(self.env_reader.env_variable("APPVEYOR_REPO_BRANCH"))
+        return ENVIRONMENT_BRANCH_VARIABLES
+            .iter()
+            .find_map(|name| self.env_reader.env_variable(name))
             .if_some(|branch| {
                 self.logger
                     .log(&format!("Found branch {} in environment", branch))
@@ -91,45 +139,184 @@ impl<'a> App<'a> {
             .if_none(|| self.logger.log("Found no branch in environment"));
     }
 
-    pub fn guess_branch(&self) -> Option<String> {
+    pub fn guess_branch(&self) -> Result<Option<String>, AppError> {
         self.logger.log("Trying to determine branch");
-        return self
-            .branch_from_svn()
-            // since guessing from a git commit is heuristic, we prefer to first check
-            // environment variables set by build runners
-            .or(self.branch_from_environment())
-            .or(self.guess_branch_from_git());
+        if let Some(branch) = self.branch_from_svn()? {
+            return Ok(Some(branch));
+        }
+        // since guessing from a git commit is heuristic, we prefer to first check
+        // environment variables set by build runners
+        if let Some(branch) = self.branch_from_environment() {
+            return Ok(Some(branch));
+        }
+        return self.first_detected_branch(self.heuristic_branch_backends());
     }
 
-    pub fn guess_timestamp(&self) -> Option<String> {
+    /// Logs and passes through the outcome of a timestamp probe: a found value, a clean
+    /// "not applicable here", or a genuine error.
+    fn log_timestamp_probe(
+        &self,
+        name: &str,
+        result: Result<Option<String>, AppError>,
+    ) -> Result<Option<String>, AppError> {
+        match &result {
+            Ok(Some(timestamp)) => self
+                .logger
+                .log(&format!("Found {} timestamp {}", name, timestamp)),
+            Ok(None) => self.logger.log(&format!("Found no {} timestamp", name)),
+            Err(error) => self
+                .logger
+                .log(&format!("Failed to determine {} timestamp: {}", name, error)),
+        }
+        return result;
+    }
+
+    pub async fn guess_timestamp(&self) -> Result<Option<String>, AppError> {
         self.logger.log("Trying to determine timestamp");
-        let svn = Svn::new(self.logger);
-        let svn_timestamp = svn
-            .timestamp()
-            .if_some(|timestamp| {
-                self.logger
-                    .log(&format!("Found SVN timestamp {}", timestamp))
-            })
-            .if_none(|| self.logger.log("Found no SVN timestamp"));
 
-        let git = Git::new(self.logger);
-        let git_timestamp = git
-            .head_timestamp()
-            .if_some(|timestamp| {
-                self.logger
-                    .log(&format!("Found Git timestamp {}", timestamp))
+        // The SVN/Git/Mercurial probes below shell out or call into libgit2
+        // synchronously, so each runs on a blocking-pool thread via spawn_blocking
+        // rather than inline in this async fn. That's what lets them actually overlap
+        // with the TFS probe's network round-trip instead of it only starting once all
+        // three local probes have already run to completion.
+        let svn_logger = Arc::clone(&self.logger);
+        let svn_root = self.vcs_root.clone();
+        let svn_probe = tokio::task::spawn_blocking(move || {
+            let svn: Box<dyn VcsBackend> = Box::new(Svn::new(&svn_logger, svn_root.as_deref()));
+            svn.timestamp()
+        });
+        let git_logger = Arc::clone(&self.logger);
+        let git_root = self.vcs_root.clone();
+        let git_probe = tokio::task::spawn_blocking(move || {
+            let git: Box<dyn VcsBackend> = Box::new(Git::new(&git_logger, git_root.as_deref()));
+            git.timestamp()
+        });
+        let hg_logger = Arc::clone(&self.logger);
+        let hg_root = self.vcs_root.clone();
+        let hg_probe = tokio::task::spawn_blocking(move || {
+            let hg: Box<dyn VcsBackend> = Box::new(Hg::new(&hg_logger, hg_root.as_deref()));
+            hg.timestamp()
+        });
+        let tfs_probe = async {
+            let tfs = Tfs::new(&self.logger, &self.env_reader);
+            tfs.timestamp(self.tfs_personal_access_token)
+                .await
+                .map_err(AppError::from)
+        };
+
+        // Run the probes concurrently: the TFS lookup does a network round-trip that
+        // would otherwise dominate wall-clock time while SVN/Git/Mercurial are sitting idle.
+        let (svn_result, git_result, hg_result, tfs_result) =
+            tokio::join!(svn_probe, git_probe, hg_probe, tfs_probe);
+
+        let svn_result = svn_result.expect("the SVN probe thread panicked");
+        let git_result = git_result.expect("the Git probe thread panicked");
+        let hg_result = hg_result.expect("the Mercurial probe thread panicked");
+
+        let svn_result = self.log_timestamp_probe("SVN", svn_result);
+        let git_result = self.log_timestamp_probe("Git", git_result);
+        let hg_result = self.log_timestamp_probe("Mercurial", hg_result);
+        // neutral label: the TFS backend resolves both TFVC changesets and Azure
+        // DevOps Git commits, and "TFVC" would mislead bug reports for the latter
+        let tfs_result = self.log_timestamp_probe("TFS", tfs_result);
+
+        // preserve the existing SVN -> Git -> Mercurial -> TFS priority order when
+        // selecting among the results; a found timestamp always wins even if a
+        // lower-priority probe errored, and we only surface an error once nothing could
+        // be resolved at all
+        if let Ok(Some(timestamp)) = svn_result {
+            return Ok(Some(timestamp));
+        }
+        if let Ok(Some(timestamp)) = git_result {
+            return Ok(Some(timestamp));
+        }
+        if let Ok(Some(timestamp)) = hg_result {
+            return Ok(Some(timestamp));
+        }
+        if let Ok(Some(timestamp)) = tfs_result {
+            return Ok(Some(timestamp));
+        }
+        svn_result?;
+        git_result?;
+        hg_result?;
+        return tfs_result;
+    }
+
+    /// Runs every branch/timestamp probe and records its outcome in a structured,
+    /// machine-readable [`Report`], instead of stopping at the first one that succeeds.
+    /// Meant to be dumped to STDOUT via `--diagnose` and attached to bug reports when
+    /// detection misbehaves.
+    ///
+    /// Each backend's probe is run exactly once here and reused to derive
+    /// `selected_branch`/`selected_timestamp`, rather than calling
+    /// [`App::guess_branch`]/[`App::guess_timestamp`] afterwards, which would spin up a
+    /// fresh set of backends and run every probe all over again. The selection below
+    /// assumes at most one of SVN/Git/Mercurial is ever actually detected for a given
+    /// `vcs_root`, which holds in practice (a single directory tree is only ever one
+    /// VCS's working copy), so "first Ok(Some(..)) in priority order" is equivalent to
+    /// `guess_branch`/`guess_timestamp`'s "first backend that detects" logic.
+    ///
+    /// Note this probes SVN/Git/Mercurial inline, directly on the async executor thread
+    /// (no `spawn_blocking`) rather than via [`App::guess_timestamp`]'s approach; fine
+    /// for this one-shot diagnostic dump, but don't copy this pattern into code that
+    /// needs to run concurrently with other async work.
+    pub async fn diagnose(&self) -> Report {
+        self.logger.log("Running diagnostics");
+
+        let environment = ENVIRONMENT_BRANCH_VARIABLES
+            .iter()
+            .map(|name| EnvVarCheck {
+                name: name.to_string(),
+                value: self.env_reader.env_variable(name),
             })
-            .if_none(|| self.logger.log("Found no Git timestamp"));
+            .collect();
 
-        let tfs = Tfs::new(self.logger, &self.env_reader);
-        let tfs_timestamp = tfs
+        let svn = Svn::new(&self.logger, self.vcs_root.as_deref());
+        let git = Git::new(&self.logger, self.vcs_root.as_deref());
+        let hg = Hg::new(&self.logger, self.vcs_root.as_deref());
+        let tfs = Tfs::new(&self.logger, &self.env_reader);
+
+        let svn_branch_result = svn.branch();
+        let git_branch_result = git.guess_branch();
+        let hg_branch_result = hg.branch();
+        let svn_timestamp_result = svn.timestamp();
+        let git_timestamp_result = git.head_timestamp();
+        let hg_timestamp_result = hg.timestamp();
+        let tfs_timestamp_result = tfs
             .timestamp(self.tfs_personal_access_token)
-            .if_some(|timestamp| {
-                self.logger
-                    .log(&format!("Found TFVC timestamp {}", timestamp))
-            })
-            .if_none(|| self.logger.log("Found no TFVC timestamp"));
-        return svn_timestamp.or(git_timestamp).or(tfs_timestamp);
+            .await
+            .map_err(AppError::from);
+
+        let selected_branch = svn_branch_result
+            .as_ref()
+            .ok()
+            .cloned()
+            .flatten()
+            .or_else(|| svn.branch_from_environment())
+            .or_else(|| self.branch_from_environment())
+            .or_else(|| git_branch_result.as_ref().ok().cloned().flatten())
+            .or_else(|| hg_branch_result.as_ref().ok().cloned().flatten());
+        let selected_timestamp = svn_timestamp_result
+            .as_ref()
+            .ok()
+            .cloned()
+            .flatten()
+            .or_else(|| git_timestamp_result.as_ref().ok().cloned().flatten())
+            .or_else(|| hg_timestamp_result.as_ref().ok().cloned().flatten())
+            .or_else(|| tfs_timestamp_result.as_ref().ok().cloned().flatten());
+
+        return Report {
+            environment,
+            svn_branch: ProbeOutcome::from(svn_branch_result),
+            git_branch: ProbeOutcome::from(git_branch_result),
+            hg_branch: ProbeOutcome::from(hg_branch_result),
+            svn_timestamp: ProbeOutcome::from(svn_timestamp_result),
+            git_timestamp: ProbeOutcome::from(git_timestamp_result),
+            hg_timestamp: ProbeOutcome::from(hg_timestamp_result),
+            tfs_timestamp: ProbeOutcome::from(tfs_timestamp_result),
+            selected_branch,
+            selected_timestamp,
+        };
     }
 
     /// Attempts to write revision.txt content to the given file path.
@@ -148,15 +335,20 @@ mod tests {
 
     #[test]
     fn empty_environment_means_no_branch() {
-        let branch =
-            App::new(&Logger::new(true), EnvReader::new(|_| None), None).branch_from_environment();
+        let branch = App::new(
+            Arc::new(Logger::new(true, None)),
+            EnvReader::new(|_| None),
+            None,
+            None,
+        )
+        .branch_from_environment();
         assert_eq!(None, branch);
     }
 
     #[test]
     fn read_branch_from_env_variable() {
         let branch = App::new(
-            &Logger::new(true),
+            Arc::new(Logger::new(true, None)),
             EnvReader::new(|variable| {
                 if variable == "GIT_BRANCH" {
                     return Some("the-branch".to_string());
@@ -164,6 +356,7 @@ mod tests {
                 return None;
             }),
             None,
+            None,
         )
         .branch_from_environment();
         assert_eq!(Some("the-branch".to_string()), branch);
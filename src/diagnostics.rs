@@ -0,0 +1,49 @@
+use serde::Serialize;
+
+use crate::error::AppError;
+
+/// The value of a single environment variable that was checked while guessing the
+/// branch, as observed by [`crate::app::App::diagnose`].
+#[derive(Serialize)]
+pub struct EnvVarCheck {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// The outcome of a single VCS/TFS probe: either a value was found, the probe
+/// determined it wasn't applicable here (e.g. not an SVN working copy), or it failed
+/// with a genuine error.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeOutcome {
+    Found(String),
+    NotFound,
+    Error(String),
+}
+
+impl From<Result<Option<String>, AppError>> for ProbeOutcome {
+    fn from(result: Result<Option<String>, AppError>) -> Self {
+        return match result {
+            Ok(Some(value)) => ProbeOutcome::Found(value),
+            Ok(None) => ProbeOutcome::NotFound,
+            Err(error) => ProbeOutcome::Error(error.to_string()),
+        };
+    }
+}
+
+/// Structured record of every probe attempted while guessing the branch and timestamp,
+/// meant to be attached to bug reports when detection misbehaves in a CI job. Printed as
+/// JSON via `--diagnose`.
+#[derive(Serialize)]
+pub struct Report {
+    pub environment: Vec<EnvVarCheck>,
+    pub svn_branch: ProbeOutcome,
+    pub git_branch: ProbeOutcome,
+    pub hg_branch: ProbeOutcome,
+    pub svn_timestamp: ProbeOutcome,
+    pub git_timestamp: ProbeOutcome,
+    pub hg_timestamp: ProbeOutcome,
+    pub tfs_timestamp: ProbeOutcome,
+    pub selected_branch: Option<String>,
+    pub selected_timestamp: Option<String>,
+}
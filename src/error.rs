@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+use crate::tfs::TfsError;
+use crate::utils::RunError;
+
+/// Crate-wide error type. Unlike the old `Option`-swallowing signatures, this lets
+/// callers distinguish "no VCS detected here" (which is not an error, returned as
+/// `Ok(None)`) from "a VCS was detected but looking up its state failed" (returned as
+/// `Err`), so misconfiguration can make the process fail loudly instead of silently
+/// writing no timestamp.
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("Failed to run external command: {0}")]
+    CommandFailed(#[from] RunError),
+
+    #[error("Failed to inspect Git repository state: {0}")]
+    VcsDetectionFailed(#[from] git2::Error),
+
+    #[error("Failed to determine the TFS timestamp: {0}")]
+    Tfs(#[from] TfsError),
+}
@@ -1,68 +1,101 @@
-extern crate regex;
+use std::path::Path;
 
-use crate::logger::Logger;
-use crate::utils::run;
+use git2::{BranchType, Repository};
 
-use self::regex::Regex;
+use crate::error::AppError;
+use crate::logger::Logger;
+use crate::vcs::VcsBackend;
 
-/// Struct for retrieving info from a git repo.
+/// Struct for retrieving info from a git repo, backed by libgit2 instead of shelling
+/// out to the `git` binary.
 pub struct Git<'a> {
     logger: &'a Logger,
+    repo: Option<Repository>,
 }
 
 impl<'a> Git<'a> {
-    pub fn new(logger: &'a Logger) -> Git<'a> {
-        return Git { logger };
-    }
-
-    /// Runs git with the given arguments and returns the result if the git command succeeded.
-    fn git(&self, args: &[&str]) -> Option<String> {
-        self.logger.log(&format!("Running git {}", args.join(" ")));
-        return match run("git", args, |command| command) {
-            Ok(stdout) => Some(stdout),
-            Err(error) => {
-                self.logger.log(&error);
+    /// `root` is the directory to look for a Git repo in, typically the VCS root
+    /// discovered by [`crate::vcs::find_vcs_root`]. Passing `None` means no such root
+    /// was found (e.g. the configured `--max-uplevel` was exceeded), so this is treated
+    /// the same as "not a Git repo" without even trying to open one.
+    pub fn new(logger: &'a Logger, root: Option<&Path>) -> Git<'a> {
+        let repo = match root {
+            Some(root) => match Repository::open(root) {
+                Ok(repo) => {
+                    logger.log("Current directory is in git");
+                    Some(repo)
+                }
+                Err(error) => {
+                    logger.log(&format!("Current directory is not in git: {}", error));
+                    None
+                }
+            },
+            None => {
+                logger.log("Current directory is not in git");
                 None
             }
         };
+        return Git { logger, repo };
     }
 
-    /// Checks if the current directory is part of some Git repo.
-    fn is_git(&self) -> bool {
-        let opt_stdout = self.git(&["rev-parse", "--is-inside-work-tree"]);
-
-        match opt_stdout {
-            Some(ref stdout) if stdout.trim().eq("true") => {
-                self.logger.log("Current directory is in git");
-                return true;
-            }
-            _ => {
-                self.logger.log("Current directory is not in git");
-                return false;
-            }
-        }
+    /// Returns the TS timestamp for the checked out commit. `Ok(None)` means the current
+    /// directory isn't a Git repo at all (or has no commits yet); `Err` means it is, but
+    /// reading HEAD failed.
+    pub fn head_timestamp(&self) -> Result<Option<String>, AppError> {
+        let repo = match &self.repo {
+            Some(repo) => repo,
+            None => return Ok(None),
+        };
+        let head = match self.head(repo)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let commit = head.peel_to_commit()?;
+        // git2's Time is seconds-since-epoch; match the "<secs><millis:03>" convention
+        // used throughout the rest of the tool, even though git commit times have no
+        // sub-second resolution.
+        return Ok(Some(format!("{}000", commit.time().seconds())));
     }
 
-    /// Returns the TS timestamp for the checked out commit.
-    pub fn head_timestamp(&self) -> Option<String> {
-        if !self.is_git() {
-            return None;
-        }
-        return self.git(&["--no-pager", "log", "-n1", "--format=%ct000"]);
+    /// Resolves HEAD, treating an unborn branch (a freshly `git init`'d repo with no
+    /// commits yet) the same as "not a Git repo": `Ok(None)`, not a hard failure.
+    fn head<'r>(&self, repo: &'r Repository) -> Result<Option<git2::Reference<'r>>, AppError> {
+        return match repo.head() {
+            Ok(head) => Ok(Some(head)),
+            Err(error)
+                if error.code() == git2::ErrorCode::UnbornBranch
+                    || error.code() == git2::ErrorCode::NotFound =>
+            {
+                self.logger
+                    .log("Current directory is a Git repo with no commits yet");
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        };
     }
 
-    fn preprocess_branch_text(branch_text: &str) -> Vec<String> {
-        let lines = branch_text.lines();
-        let branch_regex = Regex::new("^\\s*[*]\\s*").unwrap();
-
-        return lines
-            .map(|line| branch_regex.replace_all(line.trim(), "").to_string())
-            .filter(|branch| !branch.contains("HEAD detached"))
-            .collect();
+    /// Mirrors `git branch --contains <target>`: a branch "contains" the commit if its
+    /// tip either *is* that commit or descends from it, checked via `graph_descendant_of`
+    /// rather than parsing `git branch --contains` output.
+    fn branches_containing(
+        &self,
+        repo: &Repository,
+        target: git2::Oid,
+    ) -> Result<Vec<String>, AppError> {
+        let branches = repo.branches(Some(BranchType::Local))?;
+        return Ok(branches
+            .filter_map(|result| result.ok())
+            .filter(|(branch, _)| match branch.get().target() {
+                Some(tip) => {
+                    tip == target || repo.graph_descendant_of(tip, target).unwrap_or(false)
+                }
+                None => false,
+            })
+            .filter_map(|(branch, _)| branch.name().ok().flatten().map(|name| name.to_string()))
+            .collect());
     }
 
-    fn extract_single_branch(&self, branch_text: &str) -> Option<String> {
-        let branches = Git::preprocess_branch_text(branch_text);
+    fn extract_single_branch(&self, branches: Vec<String>) -> Option<String> {
         match branches.len() {
             0 => {
                 self.logger
@@ -74,7 +107,7 @@ impl<'a> Git<'a> {
                     "Found exactly one branch in the Git repo that contains the HEAD commit: {}",
                     branches.first().unwrap()
                 ));
-                return branches.first().map(|branch| branch.to_string());
+                return branches.into_iter().next();
             }
             _ => {
                 self.logger.log(&format!(
@@ -86,15 +119,112 @@ impl<'a> Git<'a> {
         }
     }
 
+    /// Resolves the shorthand name of the remote's default branch from the
+    /// `refs/remotes/origin/HEAD` symbolic ref (e.g. "main" from "refs/remotes/origin/main"),
+    /// equivalent to `git symbolic-ref refs/remotes/origin/HEAD`.
+    fn remote_default_branch(&self, repo: &Repository) -> Option<String> {
+        let reference = repo.find_reference("refs/remotes/origin/HEAD").ok()?;
+        let target = reference.symbolic_target()?;
+        return target
+            .strip_prefix("refs/remotes/origin/")
+            .map(|name| name.to_string());
+    }
+
+    /// Resolves the shorthand name (e.g. "feature", stripped of the remote prefix) of
+    /// the remote-tracking branch whose tip is exactly `target`. Detached HEAD has no
+    /// local branch to ask for its `@{upstream}`, but CI checkouts of a specific commit
+    /// typically still fetch the corresponding remote-tracking ref alongside it, so this
+    /// is the closest available equivalent of "the checkout's own tracking branch".
+    fn tracking_branch_for(&self, repo: &Repository, target: git2::Oid) -> Option<String> {
+        let branches = repo.branches(Some(BranchType::Remote)).ok()?;
+        return branches
+            .filter_map(|result| result.ok())
+            .find(|(branch, _)| branch.get().target() == Some(target))
+            .and_then(|(branch, _)| branch.name().ok().flatten().map(|name| name.to_string()))
+            .and_then(|name| name.split_once('/').map(|(_, short_name)| short_name.to_string()));
+    }
+
+    /// When more than one local branch contains the HEAD commit, tries to pick the
+    /// "real" one deterministically instead of giving up: prefer a candidate matching
+    /// the commit's own remote-tracking branch, then a candidate matching the remote's
+    /// default branch. Only returns `None` (truly ambiguous) if neither signal narrows
+    /// it down to one.
+    fn disambiguate(&self, repo: &Repository, branches: &[String], head: git2::Oid) -> Option<String> {
+        if let Some(tracking_branch) = self.tracking_branch_for(repo, head) {
+            if branches.contains(&tracking_branch) {
+                self.logger.log(&format!(
+                    "Preferring {} because it's the commit's own remote-tracking branch",
+                    tracking_branch
+                ));
+                return Some(tracking_branch);
+            }
+        }
+
+        if let Some(default_branch) = self.remote_default_branch(repo) {
+            if branches.contains(&default_branch) {
+                self.logger.log(&format!(
+                    "Preferring {} because it's the remote's default branch",
+                    default_branch
+                ));
+                return Some(default_branch);
+            }
+        }
+
+        self.logger
+            .log("Could not disambiguate using the remote tracking or default branch");
+        return None;
+    }
+
     /// Last resort: try to guess the branch from the checked out commit.
-    /// Will list all local branches this commit is part of. If there's exactly one,
-    /// returns that. Otherwise returns None.
-    pub fn guess_branch(&self) -> Option<String> {
-        if !self.is_git() {
-            return None;
+    /// If HEAD points directly at a branch, returns that branch's name. Otherwise (HEAD
+    /// detached, e.g. in many CI checkouts) lists all local branches this commit is part
+    /// of. If there's exactly one, returns that. If there's more than one, tries to
+    /// disambiguate using the upstream/remote default branch. Otherwise returns `Ok(None)`.
+    pub fn guess_branch(&self) -> Result<Option<String>, AppError> {
+        let repo = match &self.repo {
+            Some(repo) => repo,
+            None => return Ok(None),
+        };
+        let head = match self.head(repo)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+
+        if !repo.head_detached().unwrap_or(false) {
+            return Ok(head.shorthand().map(|name| name.to_string()));
+        }
+
+        self.logger
+            .log("HEAD is detached, looking for a local branch pointing at the same commit");
+        let head_oid = match head.target() {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+        let branches = self.branches_containing(repo, head_oid)?;
+        if branches.len() > 1 {
+            if let Some(branch) = self.disambiguate(repo, &branches, head_oid) {
+                return Ok(Some(branch));
+            }
         }
-        let opt_branches = self.git(&["branch", "--contains"]);
-        return opt_branches.and_then(|branch_text| self.extract_single_branch(&branch_text));
+        return Ok(self.extract_single_branch(branches));
+    }
+}
+
+impl<'a> VcsBackend for Git<'a> {
+    fn name(&self) -> &str {
+        return "Git";
+    }
+
+    fn detect(&self) -> bool {
+        return self.repo.is_some();
+    }
+
+    fn branch(&self) -> Result<Option<String>, AppError> {
+        return self.guess_branch();
+    }
+
+    fn timestamp(&self) -> Result<Option<String>, AppError> {
+        return self.head_timestamp();
     }
 }
 
@@ -103,19 +233,20 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_preprocess_branch_text() {
-        assert_eq!(["master"], Git::preprocess_branch_text("master").as_slice());
-        assert_eq!(
-            ["master"],
-            Git::preprocess_branch_text("* master").as_slice()
-        );
+    fn test_extract_single_branch() {
+        let logger = Logger::new(true, None);
+        let git = Git {
+            logger: &logger,
+            repo: None,
+        };
         assert_eq!(
-            ["master", "branch"],
-            Git::preprocess_branch_text("* master\nbranch").as_slice()
+            Some("master".to_string()),
+            git.extract_single_branch(vec!["master".to_string()])
         );
+        assert_eq!(None, git.extract_single_branch(vec![]));
         assert_eq!(
-            ["master"],
-            Git::preprocess_branch_text("* (HEAD detached at 6f9a90e36e6)\nmaster\n").as_slice()
+            None,
+            git.extract_single_branch(vec!["master".to_string(), "branch".to_string()])
         );
     }
 }
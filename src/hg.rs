@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+use crate::logger::Logger;
+use crate::utils::run;
+use crate::vcs::VcsBackend;
+
+/// Struct for retrieving info from a Mercurial (hg) repo.
+pub struct Hg<'a> {
+    logger: &'a Logger,
+    /// The working copy directory to run `hg` in, typically the VCS root discovered by
+    /// [`crate::vcs::find_vcs_root`]. `None` means no such root was found (e.g. the
+    /// configured `--max-uplevel` was exceeded), so `hg` is never even invoked.
+    root: Option<PathBuf>,
+}
+
+impl<'a> Hg<'a> {
+    pub fn new(logger: &'a Logger, root: Option<&Path>) -> Hg<'a> {
+        return Hg {
+            logger,
+            root: root.map(|root| root.to_path_buf()),
+        };
+    }
+
+    /// Runs hg with the given arguments and returns the result if the command succeeded.
+    fn hg(&self, args: &[&str]) -> Result<String, AppError> {
+        self.logger.log(&format!("Running hg {}", args.join(" ")));
+        let root = self.root.clone();
+        return Ok(run("hg", args, move |command| {
+            if let Some(root) = &root {
+                command.current_dir(root);
+            }
+            return command;
+        })?);
+    }
+
+    /// Checks if the current directory is part of some Mercurial repo. A failure to run
+    /// `hg` here (binary missing, not a repo, ...) just means "not Mercurial", so it's
+    /// logged and swallowed rather than treated as an error.
+    fn is_hg(&self) -> bool {
+        if self.root.is_none() {
+            self.logger.log("Current directory is not in Mercurial");
+            return false;
+        }
+        match self.hg(&["root"]) {
+            Ok(_) => {
+                self.logger.log("Current directory is in Mercurial");
+                true
+            }
+            Err(error) => {
+                self.logger.log(&format!("{}", error));
+                self.logger.log("Current directory is not in Mercurial");
+                false
+            }
+        }
+    }
+
+    /// `{date|hgdate}` renders as "<epoch-seconds> <timezone-offset-seconds>";
+    /// we only care about the first part.
+    fn parse_hgdate(hgdate: &str) -> Option<i64> {
+        return hgdate.split_whitespace().next()?.parse::<i64>().ok();
+    }
+
+    /// Returns the TS timestamp for the working copy's parent commit.
+    pub fn timestamp(&self) -> Result<Option<String>, AppError> {
+        if !self.is_hg() {
+            return Ok(None);
+        }
+        let hgdate = self
+            .hg(&["log", "-r", ".", "--template", "{date|hgdate}"])?
+            .trim()
+            .to_string();
+        return Ok(Hg::parse_hgdate(&hgdate).map(|seconds| format!("{}000", seconds)));
+    }
+
+    /// Returns the named branch of the working copy's parent commit.
+    pub fn branch(&self) -> Result<Option<String>, AppError> {
+        if !self.is_hg() {
+            return Ok(None);
+        }
+        let branch = self
+            .hg(&["log", "-r", ".", "--template", "{branch}"])?
+            .trim()
+            .to_string();
+        return Ok(if branch.is_empty() { None } else { Some(branch) });
+    }
+}
+
+impl<'a> VcsBackend for Hg<'a> {
+    fn name(&self) -> &str {
+        return "Mercurial";
+    }
+
+    fn detect(&self) -> bool {
+        return self.is_hg();
+    }
+
+    fn branch(&self) -> Result<Option<String>, AppError> {
+        return self.branch();
+    }
+
+    fn timestamp(&self) -> Result<Option<String>, AppError> {
+        return self.timestamp();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hgdate() {
+        assert_eq!(Some(1553181434), Hg::parse_hgdate("1553181434 -3600"));
+        assert_eq!(None, Hg::parse_hgdate(""));
+        assert_eq!(None, Hg::parse_hgdate("not-a-number -3600"));
+    }
+}
@@ -1,18 +1,170 @@
+extern crate chrono;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use self::chrono::Local;
+
+/// Size at which the log file is rotated, by default.
+const DEFAULT_MAX_LOG_FILE_BYTES: u64 = 1024 * 1024;
+/// Number of rotated historical log files kept around, by default.
+const DEFAULT_LOG_FILE_HISTORY: u32 = 7;
+
 pub struct Logger {
     verbose: bool,
+    file_sink: Option<Mutex<FileSink>>,
 }
 
 impl Logger {
-    pub fn new(verbose: bool) -> Logger {
-        Logger { verbose }
+    pub fn new(verbose: bool, log_file: Option<&Path>) -> Logger {
+        let file_sink = log_file.and_then(|path| {
+            match FileSink::open(
+                path.to_path_buf(),
+                DEFAULT_MAX_LOG_FILE_BYTES,
+                DEFAULT_LOG_FILE_HISTORY,
+            ) {
+                Ok(sink) => Some(Mutex::new(sink)),
+                Err(error) => {
+                    eprintln!("Failed to open log file {}: {}", path.display(), error);
+                    None
+                }
+            }
+        });
+        return Logger { verbose, file_sink };
     }
 
     pub fn log<S>(&self, message: S)
     where
         S: Into<String>,
     {
+        let message = message.into();
         if self.verbose {
-            println!("{}", message.into());
+            println!("{}", message);
+        }
+        if let Some(file_sink) = &self.file_sink {
+            if let Ok(mut file_sink) = file_sink.lock() {
+                file_sink.write_line(&message);
+            }
+        }
+    }
+}
+
+/// The optional rotating file sink behind `--log-file`. Appends a timestamped line per
+/// log message and rotates once the file grows past `max_bytes`, keeping `max_history`
+/// previous files around (`log`, `log.1`, `log.2`, ..., oldest dropped).
+struct FileSink {
+    path: PathBuf,
+    file: File,
+    max_bytes: u64,
+    max_history: u32,
+}
+
+impl FileSink {
+    fn open(path: PathBuf, max_bytes: u64, max_history: u32) -> std::io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        return Ok(FileSink {
+            path,
+            file,
+            max_bytes,
+            max_history,
+        });
+    }
+
+    fn write_line(&mut self, message: &str) {
+        let timestamp = Local::now().format("%Y/%m/%d %H:%M:%S%.3f");
+        let line = format!("{} {}\n", timestamp, message);
+        if let Err(error) = self.file.write_all(line.as_bytes()) {
+            eprintln!("Failed to write to log file {}: {}", self.path.display(), error);
+            return;
+        }
+        self.rotate_if_too_large();
+    }
+
+    fn rotate_if_too_large(&mut self) {
+        let size = match self.file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return,
+        };
+        if size < self.max_bytes {
+            return;
+        }
+
+        let oldest = Self::history_path(&self.path, self.max_history);
+        let _ = fs::remove_file(&oldest);
+        for index in (1..self.max_history).rev() {
+            let _ = fs::rename(
+                Self::history_path(&self.path, index),
+                Self::history_path(&self.path, index + 1),
+            );
+        }
+        if self.max_history > 0 {
+            let _ = fs::rename(&self.path, Self::history_path(&self.path, 1));
+        } else {
+            let _ = fs::remove_file(&self.path);
         }
+
+        match OpenOptions::new().create(true).append(true).open(&self.path) {
+            Ok(file) => self.file = file,
+            Err(error) => eprintln!(
+                "Failed to reopen log file {} after rotation: {}",
+                self.path.display(),
+                error
+            ),
+        }
+    }
+
+    fn history_path(path: &Path, index: u32) -> PathBuf {
+        let mut file_name = path.as_os_str().to_os_string();
+        file_name.push(format!(".{}", index));
+        return PathBuf::from(file_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unique_temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("teamscale-timestamp-test-log-{}", name));
+        let _ = fs::remove_file(&path);
+        return path;
+    }
+
+    #[test]
+    fn writes_timestamped_lines_to_the_log_file() {
+        let path = unique_temp_path("writes-timestamped-lines");
+        let logger = Logger::new(false, Some(&path));
+        logger.log("hello");
+        logger.log("world");
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].ends_with("hello"), "line was: {}", lines[0]);
+        assert!(lines[1].ends_with("world"), "line was: {}", lines[1]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rotates_once_the_size_threshold_is_exceeded() {
+        let path = unique_temp_path("rotates-past-threshold");
+        let rotated = FileSink::history_path(&path, 1);
+        let _ = fs::remove_file(&rotated);
+
+        let file_sink = Mutex::new(FileSink::open(path.clone(), 10, 7).unwrap());
+        {
+            let mut file_sink = file_sink.lock().unwrap();
+            file_sink.write_line("this line is long enough to exceed the threshold");
+        }
+
+        assert!(rotated.exists(), "expected {} to exist after rotation", rotated.display());
+        assert!(path.exists(), "expected a fresh log file at {}", path.display());
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
     }
 }
@@ -1,6 +1,7 @@
 extern crate clap;
 
 use std::path::Path;
+use std::sync::Arc;
 
 use clap::Arg;
 
@@ -9,14 +10,19 @@ use crate::env_reader::EnvReader;
 use crate::logger::Logger;
 
 mod app;
+mod diagnostics;
 mod env_reader;
+mod error;
 mod git;
+mod hg;
 mod logger;
 mod svn;
 mod tfs;
 mod utils;
+mod vcs;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let version = env!("CARGO_PKG_VERSION");
     let matches = clap::App::new("teamscale-timestamp")
         .version(version)
@@ -51,39 +57,106 @@ fn main() {
                 the TFS from an environment variable, uses the given personal access token to talk \
                 to the TFS REST API. The user this token belongs to must have read access to Work \
                 Items!"))
+        .arg(Arg::with_name("diagnose")
+            .long("diagnose")
+            .help("Instead of determining the branch/timestamp and writing revision.txt, prints a \
+                JSON report of every environment variable and VCS/TFS probe that was attempted to \
+                STDOUT. Attach this to bug reports when detection picks the wrong branch or \
+                timestamp."))
+        .arg(Arg::with_name("max-uplevel")
+            .long("max-uplevel")
+            .takes_value(true)
+            .value_name("N")
+            .help("Limits how many parent directories are searched for a Git/SVN/Mercurial root \
+                above the current directory. Useful in CI steps that `cd` into a module of a \
+                larger checkout and shouldn't pick up an unrelated repository further up the \
+                tree. Defaults to unlimited (search all the way to the filesystem root)."))
+        .arg(Arg::with_name("log-file")
+            .long("log-file")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Appends a timestamped trace of every VCS/TFS command and its outcome to FILE, \
+                rotating it once it exceeds 1 MiB and keeping up to 7 historical files \
+                (FILE.1, FILE.2, ...). Gives you a blackbox-style audit trail to attach to bug \
+                reports when this tool misdetects the branch/timestamp in a CI job."))
         .get_matches();
 
-    let logger = Logger::new(matches.is_present("verbose"));
+    let logger = Arc::new(Logger::new(
+        matches.is_present("verbose"),
+        matches.value_of("log-file").map(Path::new),
+    ));
     let env_reader = EnvReader::new(|name| std::env::var(name).ok());
     let tfs_access_token = matches.value_of("tfs-pat");
-    let app = App::new(&logger, env_reader, tfs_access_token);
+    let max_uplevel = matches.value_of("max-uplevel").map(|value| {
+        value.parse::<u32>().unwrap_or_else(|_| {
+            eprintln!("Invalid value for --max-uplevel, must be a non-negative integer: {}", value);
+            std::process::exit(EXIT_VCS_ERROR);
+        })
+    });
+    let app = App::new(Arc::clone(&logger), env_reader, tfs_access_token, max_uplevel);
     logger.log(&format!(
         "teamscale-timestamp v{} trying to determine branch + timestamp for an external upload",
         version
     ));
 
-    let opt_branch = matches
-        .value_of("branch")
-        .map(|branch| branch.to_string())
-        .or_else(|| app.guess_branch());
-    let opt_timestamp = app.guess_timestamp();
+    if matches.is_present("diagnose") {
+        let report = app.diagnose().await;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("failed to serialize diagnostic report")
+        );
+        return;
+    }
 
     let debug_help = "Run with --verbose for further information. If you believe this is a bug \
         in this program, please run this program with --verbose and send its output plus a detailed \
         bug report to support@teamscale.com";
 
-    match opt_branch {
-        None => panic!(
-            "Couldn't resolve the branch. Try manually passing the branch with --branch. {}",
-            debug_help
-        ),
-        Some(branch) => match opt_timestamp {
-            None => panic!("Couldn't resolve the timestamp. {}", debug_help),
-            Some(timestamp) => output(&app, branch, timestamp, matches.value_of("revfile")),
+    let opt_branch = match matches.value_of("branch") {
+        Some(branch) => Some(branch.to_string()),
+        None => match app.guess_branch() {
+            Ok(opt_branch) => opt_branch,
+            Err(error) => {
+                eprintln!("Failed to determine the branch: {}\n{}", error, debug_help);
+                std::process::exit(EXIT_VCS_ERROR);
+            }
         },
+    };
+    let opt_timestamp = match app.guess_timestamp().await {
+        Ok(opt_timestamp) => opt_timestamp,
+        Err(error) => {
+            eprintln!("Failed to determine the timestamp: {}\n{}", error, debug_help);
+            std::process::exit(EXIT_VCS_ERROR);
+        }
+    };
+
+    match (opt_branch, opt_timestamp) {
+        (None, _) => {
+            eprintln!(
+                "Couldn't resolve the branch. Try manually passing the branch with --branch. {}",
+                debug_help
+            );
+            std::process::exit(EXIT_NOT_DETERMINED);
+        }
+        (_, None) => {
+            eprintln!("Couldn't resolve the timestamp. {}", debug_help);
+            std::process::exit(EXIT_NOT_DETERMINED);
+        }
+        (Some(branch), Some(timestamp)) => {
+            output(&app, branch, timestamp, matches.value_of("revfile"))
+        }
     }
 }
 
+/// Branch or timestamp could not be determined, but without a hard error (e.g. no VCS
+/// could be detected at all). Distinct from [`EXIT_VCS_ERROR`] so CI logs can tell the two
+/// apart.
+const EXIT_NOT_DETERMINED: i32 = 1;
+/// A VCS (or the TFS) was detected, but querying it failed, e.g. bad credentials, an
+/// unparsable response, or a corrupt repository. Also used when `--max-uplevel` is given a
+/// value that is not a non-negative integer.
+const EXIT_VCS_ERROR: i32 = 2;
+
 fn output(app: &App, branch: String, timestamp: String, opt_revision_txt: Option<&str>) {
     match opt_revision_txt {
         Some(revision_text) => {
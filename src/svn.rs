@@ -1,15 +1,23 @@
 extern crate chrono;
 extern crate regex;
 
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
 use crate::logger::Logger;
 use crate::utils::run;
+use crate::vcs::VcsBackend;
 
 use self::chrono::DateTime;
 use self::regex::Regex;
 
 /// Struct for retrieving info from an SVN repo.
-pub struct Svn<'a, T: Logger> {
-    logger: &'a T,
+pub struct Svn<'a> {
+    logger: &'a Logger,
+    /// The working copy directory to run `svn` in, typically the VCS root discovered by
+    /// [`crate::vcs::find_vcs_root`]. `None` means no such root was found (e.g. the
+    /// configured `--max-uplevel` was exceeded), so `svn` is never even invoked.
+    root: Option<PathBuf>,
 }
 
 // TODO (FS) doesn't work for e.g. release/2.6 etc. document using --branch
@@ -31,57 +39,65 @@ fn svn_date_string_to_timestamp(date_string: &str) -> Option<i64> {
         .ok();
 }
 
-impl<'a, T: Logger> Svn<'a, T> {
-    pub fn new(logger: &'a T) -> Svn<'a, T> {
-        return Svn { logger };
+impl<'a> Svn<'a> {
+    pub fn new(logger: &'a Logger, root: Option<&Path>) -> Svn<'a> {
+        return Svn {
+            logger,
+            root: root.map(|root| root.to_path_buf()),
+        };
     }
 
     /// Runs SVN with the given arguments and returns the result if the command succeeded.
-    fn svn(&self, args: &[&str]) -> Option<String> {
+    fn svn(&self, args: &[&str]) -> Result<String, AppError> {
         self.logger.log(&format!("Running svn {}", args.join(" ")));
-        return match run("svn", args, |command| command.env("LANG", "C")) {
-            Ok(stdout) => Some(stdout),
-            Err(error) => {
-                self.logger.log(&error);
-                None
+        let root = self.root.clone();
+        return Ok(run("svn", args, move |command| {
+            command.env("LANG", "C");
+            if let Some(root) = &root {
+                command.current_dir(root);
             }
-        };
+            return command;
+        })?);
     }
 
-    /// Checks if the current directory is part of some SVN repo.
+    /// Checks if the current directory is part of some SVN repo. A failure to run `svn`
+    /// here (binary missing, not a working copy, ...) just means "not SVN", so it's
+    /// logged and swallowed rather than treated as an error.
     fn is_svn(&self) -> bool {
-        let opt_stdout = self.svn(&["info"]);
-
-        match opt_stdout {
-            Some(ref stdout) if stdout.contains("URL:") => {
+        if self.root.is_none() {
+            self.logger.log("Current directory is not in SVN");
+            return false;
+        }
+        match self.svn(&["info"]) {
+            Ok(ref stdout) if stdout.contains("URL:") => {
                 self.logger.log("Current directory is in SVN");
-                return true;
+                true
             }
-            _ => {
+            Ok(_) => {
                 self.logger.log("Current directory is not in SVN");
-                return false;
+                false
+            }
+            Err(error) => {
+                self.logger.log(&format!("{}", error));
+                self.logger.log("Current directory is not in SVN");
+                false
             }
         }
     }
 
-    /// Returns the TS timestamp for the currently checked out revision.
-    pub fn timestamp(&self) -> Option<String> {
+    /// Returns the TS timestamp for the currently checked out revision. `Ok(None)` means
+    /// this isn't an SVN working copy; `Err` means it is, but reading the timestamp failed.
+    pub fn timestamp(&self) -> Result<Option<String>, AppError> {
         if !self.is_svn() {
-            return None;
+            return Ok(None);
         }
-        let opt_date_string = self
-            .svn(&["info", "--show-item", "last-changed-date"])
-            .map(|string| string.trim().to_string());
-        return match opt_date_string {
-            Some(ref date_string) => {
-                self.logger
-                    .log(&format!("Read date {} from SVN", date_string));
-                let timestamp = opt_date_string
-                    .and_then(|date_string| svn_date_string_to_timestamp(&date_string));
-                return timestamp.map(|timestamp| format!("{}000", timestamp));
-            }
-            None => None,
-        };
+        let date_string = self
+            .svn(&["info", "--show-item", "last-changed-date"])?
+            .trim()
+            .to_string();
+        self.logger
+            .log(&format!("Read date {} from SVN", date_string));
+        return Ok(svn_date_string_to_timestamp(&date_string).map(|timestamp| format!("{}000", timestamp)));
     }
 
     /// Tries to read the SVN branch form environment variables.
@@ -96,20 +112,33 @@ impl<'a, T: Logger> Svn<'a, T> {
             .and_then(|url| extract_branch_from_url(&url));
     }
 
-    /// Extracts the branch from the SVN URL of the current directory.
-    pub fn branch(&self) -> Option<String> {
+    /// Extracts the branch from the SVN URL of the current directory. `Ok(None)` means
+    /// this isn't an SVN working copy; `Err` means it is, but reading the URL failed.
+    pub fn branch(&self) -> Result<Option<String>, AppError> {
         if !self.is_svn() {
-            return None;
+            return Ok(None);
         }
-        let opt_url = self.svn(&["info", "--show-item", "url"]);
-        return match opt_url {
-            Some(url) => {
-                self.logger
-                    .log(&format!("Trying to parse SVN URL: {}", url));
-                return extract_branch_from_url(&url);
-            }
-            None => None,
-        };
+        let url = self.svn(&["info", "--show-item", "url"])?;
+        self.logger.log(&format!("Trying to parse SVN URL: {}", url));
+        return Ok(extract_branch_from_url(&url));
+    }
+}
+
+impl<'a> VcsBackend for Svn<'a> {
+    fn name(&self) -> &str {
+        return "SVN";
+    }
+
+    fn detect(&self) -> bool {
+        return self.is_svn();
+    }
+
+    fn branch(&self) -> Result<Option<String>, AppError> {
+        return self.branch();
+    }
+
+    fn timestamp(&self) -> Result<Option<String>, AppError> {
+        return self.timestamp();
     }
 }
 
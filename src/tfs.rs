@@ -1,21 +1,29 @@
 use std::error::Error;
 use std::fmt::Display;
 use std::fmt::Formatter;
-use std::io::Read;
 
 use chrono::DateTime;
-use reqwest::{RedirectPolicy, RequestBuilder, Response, StatusCode, Url};
+use reqwest::{redirect::Policy, RequestBuilder, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
 use crate::env_reader::EnvReader;
 use crate::logger::Logger;
 
-/// Retries info from a TFVC repo.
+/// Retries info from a TFS, either a TFVC repo or an Azure DevOps Git repo.
 pub struct Tfs<'a> {
     logger: &'a Logger,
     env_reader: &'a EnvReader<'a>,
 }
 
+/// Which kind of repository the TFS build is backed by, as reported by the
+/// `BUILD_REPOSITORY_PROVIDER` environment variable. Each kind is queried through a
+/// different REST API and yields a differently-shaped response.
+enum RepositoryKind {
+    Tfvc,
+    Git { repository_id: String },
+}
+
 /// The type of access token to use when connecting to the TFS.
 enum AccessToken {
     Personal(String),
@@ -32,76 +40,122 @@ impl AccessToken {
     }
 }
 
-/// JSON response from the TFS for a changeset.
+/// JSON response from the TFS for a TFVC changeset.
 #[derive(Deserialize)]
 struct ChangesetResponse {
     #[serde(rename = "createdDate")]
     created_date: String,
 }
 
+/// JSON response from the TFS for an Azure DevOps Git commit.
+#[derive(Deserialize)]
+struct GitCommitResponse {
+    committer: GitCommitUser,
+}
+
+#[derive(Deserialize)]
+struct GitCommitUser {
+    date: String,
+}
+
 impl<'a> Tfs<'a> {
     pub fn new(logger: &'a Logger, env_reader: &'a EnvReader) -> Tfs<'a> {
         return Tfs { logger, env_reader };
     }
 
     /// Guesses the timestamp to which to upload the external analysis result based on the
-    /// changeset reported by the TFS. Does a network request to determine the changeset's
-    /// creation time.
-    pub fn timestamp(&self, personal_access_token: Option<&str>) -> Option<String> {
-        let teamproject = self.env_reader.env_variable("SYSTEM_TEAMPROJECTID")?;
-        let changeset = self.env_reader.env_variable("BUILD_SOURCEVERSION")?;
-        let collection_uri = self
+    /// changeset (TFVC) or commit (Azure DevOps Git) reported by the TFS. Does a network
+    /// request to determine its creation time. Returns `Ok(None)` when the required TFS
+    /// environment variables are not present (i.e. we're not running in a TFS/Azure
+    /// DevOps pipeline), and `Err` when they are present but the lookup itself failed.
+    pub async fn timestamp(
+        &self,
+        personal_access_token: Option<&str>,
+    ) -> Result<Option<String>, TfsError> {
+        let teamproject = match self.env_reader.env_variable("SYSTEM_TEAMPROJECTID") {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let changeset = match self.env_reader.env_variable("BUILD_SOURCEVERSION") {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let collection_uri = match self
             .env_reader
-            .env_variable("SYSTEM_TEAMFOUNDATIONCOLLECTIONURI")?;
-        return match self.timestamp_or_error(
-            collection_uri,
-            teamproject,
-            changeset,
-            personal_access_token,
-        ) {
-            Ok(timestamp) => Some(timestamp),
-            Err(error) => {
-                self.logger.log(&format!("{}", error));
-                None
-            }
+            .env_variable("SYSTEM_TEAMFOUNDATIONCOLLECTIONURI")
+        {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let repository_kind = match self
+            .env_reader
+            .env_variable("BUILD_REPOSITORY_PROVIDER")
+            .as_deref()
+        {
+            Some("TfsGit") => match self.env_reader.env_variable("BUILD_REPOSITORY_ID") {
+                Some(repository_id) => RepositoryKind::Git { repository_id },
+                None => return Ok(None),
+            },
+            // legacy TFVC pipelines report "TfsVersionControl" here, but we also fall
+            // back to TFVC for any other/unset value to preserve old behavior
+            _ => RepositoryKind::Tfvc,
         };
+        return self
+            .timestamp_or_error(
+                collection_uri,
+                teamproject,
+                changeset,
+                repository_kind,
+                personal_access_token,
+            )
+            .await
+            .map(Some);
     }
 
-    fn timestamp_or_error(
+    async fn timestamp_or_error(
         &self,
         collection_uri: String,
         teamproject: String,
         changeset: String,
+        repository_kind: RepositoryKind,
         personal_access_token: Option<&str>,
     ) -> TfsResult<String> {
-        let url = self.create_changeset_url(collection_uri, teamproject, changeset);
+        let url = self.create_url(collection_uri, teamproject, changeset, &repository_kind);
         let access_token = match personal_access_token {
             Some(token) => AccessToken::Personal(token.to_string()),
             None => AccessToken::Oauth(self.get_access_token()?),
         };
-        let response = self.request(url, access_token)?;
-        let changeset_response = self.parse_response(response)?;
-        return parse_date(changeset_response.created_date);
+        let response = self.request(url, access_token).await?;
+        return match repository_kind {
+            RepositoryKind::Tfvc => {
+                let changeset_response = self.parse_response::<ChangesetResponse>(response).await?;
+                parse_date(changeset_response.created_date)
+            }
+            RepositoryKind::Git { .. } => {
+                let commit_response = self.parse_response::<GitCommitResponse>(response).await?;
+                parse_date(commit_response.committer.date)
+            }
+        };
     }
 
-    fn parse_response(&self, mut response: Response) -> TfsResult<ChangesetResponse> {
-        let mut string = String::new();
-        response
-            .read_to_string(&mut string)
+    async fn parse_response<T: DeserializeOwned>(&self, response: Response) -> TfsResult<T> {
+        let string = response
+            .text()
+            .await
             .map_err(TfsError::CannotReadRequestBody)?;
-        return serde_json::from_str::<ChangesetResponse>(&string)
+        return serde_json::from_str::<T>(&string)
             .map_err(|error| TfsError::JsonParseFailed(error, string));
     }
 
-    fn request(&self, url: Url, access_token: AccessToken) -> TfsResult<Response> {
+    async fn request(&self, url: Url, access_token: AccessToken) -> TfsResult<Response> {
         self.logger.log(format!("Requesting URL {}", url));
         let client = reqwest::ClientBuilder::new()
             .danger_accept_invalid_certs(true)
             .danger_accept_invalid_hostnames(true)
-            .redirect(RedirectPolicy::none())
+            .redirect(Policy::none())
             .build()
             .unwrap();
-        let response = access_token.configure(client.get(url)).send()?;
+        let response = access_token.configure(client.get(url)).send().await?;
 
         if is_tfs_signin_redirect(&response) {
             return Err(TfsError::InvalidAccessToken());
@@ -112,17 +166,24 @@ impl<'a> Tfs<'a> {
         Ok(response)
     }
 
-    fn create_changeset_url(
+    fn create_url(
         &self,
         collection_uri: String,
         teamproject: String,
         changeset: String,
+        repository_kind: &RepositoryKind,
     ) -> Url {
-        let url_string = &format!(
-            "{}/{}/_apis/tfvc/changesets/{}",
-            collection_uri, teamproject, changeset
-        );
-        return Url::parse(url_string).unwrap();
+        let url_string = match repository_kind {
+            RepositoryKind::Tfvc => format!(
+                "{}/{}/_apis/tfvc/changesets/{}",
+                collection_uri, teamproject, changeset
+            ),
+            RepositoryKind::Git { repository_id } => format!(
+                "{}/{}/_apis/git/repositories/{}/commits/{}",
+                collection_uri, teamproject, repository_id, changeset
+            ),
+        };
+        return Url::parse(&url_string).unwrap();
     }
 
     fn get_access_token(&self) -> TfsResult<String> {
@@ -156,10 +217,10 @@ fn parse_date(date_string: String) -> TfsResult<String> {
 
 /// All errors that can occurr when trying to determine the timestamp of a TFVC changeset.
 #[derive(Debug)]
-enum TfsError {
+pub(crate) enum TfsError {
     JsonParseFailed(serde_json::error::Error, String),
     RequestTimedOut(reqwest::Error),
-    CannotReadRequestBody(std::io::Error),
+    CannotReadRequestBody(reqwest::Error),
     TfsInternalServerError(reqwest::Error),
     AccessTokenNotProvided(),
     InvalidAccessToken(),
@@ -244,7 +305,7 @@ impl From<reqwest::Error> for TfsError {
         if error.is_timeout() {
             return TfsError::RequestTimedOut(error);
         }
-        if error.is_server_error() {
+        if error.status().map_or(false, |status| status.is_server_error()) {
             return TfsError::TfsInternalServerError(error);
         }
         return TfsError::OtherRequestError(error);
@@ -280,6 +341,32 @@ mod tests {
         assert_eq!(changeset.created_date, "2019-03-10T15:27:14.803Z");
     }
 
+    #[test]
+    fn test_parse_git_commit_response() {
+        let json = r#"{"commitId":"aad331d8","committer":{"name":"CQSE GmbH","email":"microsoft@cqse.eu","date":"2019-03-10T15:27:14.803Z"},"comment":"baseless merge v1.5 -> v2"}"#;
+        let commit: GitCommitResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(commit.committer.date, "2019-03-10T15:27:14.803Z");
+    }
+
+    #[test]
+    fn test_create_url_for_git_repository() {
+        let logger = Logger::new(true, None);
+        let env_reader = EnvReader::new(|_| None);
+        let tfs = Tfs::new(&logger, &env_reader);
+        let url = tfs.create_url(
+            "https://cqse.visualstudio.com".to_string(),
+            "TestData".to_string(),
+            "aad331d8".to_string(),
+            &RepositoryKind::Git {
+                repository_id: "repo-id".to_string(),
+            },
+        );
+        assert_eq!(
+            url.as_str(),
+            "https://cqse.visualstudio.com/TestData/_apis/git/repositories/repo-id/commits/aad331d8"
+        );
+    }
+
     #[test]
     fn test_parse_timestamp() {
         assert_eq!(
@@ -292,32 +379,38 @@ mod tests {
         );
     }
 
-    ///#[test]
-    fn test_request() {
+    ///#[tokio::test]
+    async fn test_request() {
         let access_token = std::env::var("TFS_ACCESS_TOKEN").unwrap();
-        let logger = Logger::new(true);
+        let logger = Logger::new(true, None);
         let env_reader = EnvReader::new(|_| None);
         let tfs = Tfs::new(&logger, &env_reader);
-        let result = tfs.timestamp_or_error(
-            "https://cqse.visualstudio.com".to_string(),
-            "TestData".to_string(),
-            "27754".to_string(),
-            Some(access_token.as_str()),
-        );
+        let result = tfs
+            .timestamp_or_error(
+                "https://cqse.visualstudio.com".to_string(),
+                "TestData".to_string(),
+                "27754".to_string(),
+                RepositoryKind::Tfvc,
+                Some(access_token.as_str()),
+            )
+            .await;
         assert_eq!(result.unwrap(), "1552231634803".to_string());
     }
 
-    #[test]
-    fn test_invalid_access_token() {
-        let logger = Logger::new(true);
+    #[tokio::test]
+    async fn test_invalid_access_token() {
+        let logger = Logger::new(true, None);
         let env_reader = EnvReader::new(|_| None);
         let tfs = Tfs::new(&logger, &env_reader);
-        let result = tfs.timestamp_or_error(
-            "https://cqse.visualstudio.com".to_string(),
-            "TestData".to_string(),
-            "27754".to_string(),
-            Some("invalid"),
-        );
+        let result = tfs
+            .timestamp_or_error(
+                "https://cqse.visualstudio.com".to_string(),
+                "TestData".to_string(),
+                "27754".to_string(),
+                RepositoryKind::Tfvc,
+                Some("invalid"),
+            )
+            .await;
 
         let error = result.err();
         match error {
@@ -1,3 +1,4 @@
+use std::io;
 use std::process::Command;
 
 pub trait PeekOption<T> {
@@ -31,23 +32,60 @@ impl<T> PeekOption<T> for Option<T> {
     }
 }
 
-pub fn run(program: &str, args: &[&str], configurator: fn(&mut Command) -> &mut Command) -> Result<String, String> {
-    let opt_output = configurator(Command::new(program).args(args)).output();
+/// Error produced when running an external command. Distinguishes failing to even start
+/// the process (e.g. the binary isn't on PATH) from a clean non-zero exit, which most VCS
+/// CLIs also use to signal "not applicable here" (e.g. `svn info` outside a working copy).
+#[derive(Debug)]
+pub enum RunError {
+    Spawn(String, io::Error),
+    NonZeroExit {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+}
 
-    match opt_output {
-        Ok(output) => {
-            if !output.status.success() {
-                return Err(format!("{} {} failed with exit code {}", program, args.join(" "),
-                                   output.status.code().unwrap_or(-999)));
-            }
-            return Ok(std::str::from_utf8(output.stdout.as_ref()).unwrap().to_string());
-        }
-        Err(error) => {
-            return Err(format!("{} {} failed: {}", program, args.join(" "), error.to_string()));
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Spawn(command, error) => write!(f, "{} failed to start: {}", command, error),
+            RunError::NonZeroExit {
+                command,
+                code,
+                stderr,
+            } => write!(
+                f,
+                "{} failed with exit code {}: {}",
+                command,
+                code.map_or("<unknown>".to_string(), |code| code.to_string()),
+                stderr.trim()
+            ),
         }
     }
 }
 
+impl std::error::Error for RunError {}
+
+pub fn run(
+    program: &str,
+    args: &[&str],
+    configurator: impl Fn(&mut Command) -> &mut Command,
+) -> Result<String, RunError> {
+    let command_line = format!("{} {}", program, args.join(" "));
+    let output = configurator(Command::new(program).args(args))
+        .output()
+        .map_err(|error| RunError::Spawn(command_line.clone(), error))?;
+
+    if !output.status.success() {
+        return Err(RunError::NonZeroExit {
+            command: command_line,
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    return Ok(std::str::from_utf8(output.stdout.as_ref()).unwrap().to_string());
+}
+
 #[cfg(test)]
 mod test {
     use crate::utils::run;
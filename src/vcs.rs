@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+
+/// Common interface implemented by each locally-detectable VCS backend (Git, SVN,
+/// Mercurial, ...). Adding support for a new VCS is a matter of implementing this trait
+/// for it and registering an instance in `App`'s backend lists; callers don't need to
+/// touch the core detection flow.
+///
+/// The TFS/Azure DevOps backend deliberately isn't part of this pipeline: it's queried
+/// over the network rather than detected from the local directory, and is probed
+/// concurrently with these backends instead (see `App::guess_timestamp`).
+pub trait VcsBackend {
+    /// A short, human-readable name used in log output, e.g. "Git".
+    fn name(&self) -> &str;
+
+    /// Whether the current directory appears to be managed by this VCS.
+    fn detect(&self) -> bool;
+
+    fn branch(&self) -> Result<Option<String>, AppError>;
+
+    fn timestamp(&self) -> Result<Option<String>, AppError>;
+}
+
+/// Ascends from `start`, looking at each level for a directory containing one of
+/// `markers` (e.g. `.git`, `.svn`, `.hg`). Stops after `max_uplevel` parent directories
+/// have been tried (`None` means ascend all the way to the filesystem root) and returns
+/// `None` if no marker was found within that limit.
+///
+/// This exists because running the tool from a subdirectory of a checkout (common in CI
+/// steps that `cd` into a module) should still find the enclosing VCS root, without
+/// accidentally walking past a configured boundary into an unrelated parent checkout.
+pub fn find_vcs_root(start: &Path, markers: &[&str], max_uplevel: Option<u32>) -> Option<PathBuf> {
+    let mut dir = start.to_path_buf();
+    let mut levels_climbed = 0;
+    loop {
+        if markers.iter().any(|marker| dir.join(marker).exists()) {
+            return Some(dir);
+        }
+        if max_uplevel.map_or(false, |limit| levels_climbed >= limit) {
+            return None;
+        }
+        dir = match dir.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return None,
+        };
+        levels_climbed += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn unique_temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("teamscale-timestamp-test-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn finds_marker_in_an_ancestor_directory() {
+        let root = unique_temp_dir("finds-marker-in-ancestor");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(
+            Some(root.clone()),
+            find_vcs_root(&nested, &[".git"], None)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn gives_up_once_the_uplevel_limit_is_exceeded() {
+        let root = unique_temp_dir("gives-up-past-limit");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+
+        // "a/b" -> "a" is 1 level, "a" -> root is 2 levels; the marker lives in root itself.
+        assert_eq!(None, find_vcs_root(&nested, &[".git"], Some(1)));
+        assert_eq!(
+            Some(root.clone()),
+            find_vcs_root(&nested, &[".git"], Some(2))
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}